@@ -4,11 +4,14 @@ use std::fs;
 use std::io;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
 
 use anyhow::{bail, Context, Result};
 use clap::{Command, CommandFactory, Parser};
 use clap_complete::{generate, Generator, Shell};
 use clap_derive::{Args, Parser};
+use globset::GlobBuilder;
 use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
 use serde::{Deserialize, Serialize};
@@ -25,6 +28,10 @@ struct Files {
     /// Run batch process for all vendor packages
     #[arg(long, short)]
     all: bool,
+    /// Update checksum for vendored files matching a glob pattern, matched relative to the
+    /// vendor root (e.g. `serde-*/src/**/*.rs`)
+    #[arg(long, value_name = "PATTERN")]
+    glob: Option<String>,
 
     #[arg(long, required(false), value_name = "SHELL")]
     completion: Option<Shell>,
@@ -41,6 +48,23 @@ struct Cli {
     /// Set 'true' to remove checksum for missing files
     #[arg(long)]
     ignore_missing: bool,
+    /// Verify recorded checksums against the files on disk instead of rewriting them
+    #[arg(long)]
+    check: bool,
+    /// Sidecar cache file mapping file size+mtime to checksum, to skip rehashing unchanged files
+    #[arg(long, value_name = "PATH")]
+    cache: Option<PathBuf>,
+    /// With --packages/--all, rebuild the file list from what's actually on disk instead of only
+    /// refreshing already-recorded files, picking up files added to the package; recorded files
+    /// that are gone from disk are dropped with --ignore-missing and are an error otherwise
+    #[arg(long, conflicts_with_all = ["check", "files_in_vendor_dir", "glob"])]
+    sync: bool,
+    /// Set the top-level `package` checksum field to a known crate-tarball digest
+    #[arg(long, value_name = "SHA256", conflicts_with_all = ["clear_package", "check", "files_in_vendor_dir", "glob"])]
+    set_package: Option<String>,
+    /// Clear the top-level `package` checksum field, e.g. for a locally patched package
+    #[arg(long, conflicts_with_all = ["check", "files_in_vendor_dir", "glob"])]
+    clear_package: bool,
     /// Limit the number of threads or this number will be set automatically
     #[arg(long, value_name("NUM"))]
     num_threads: Option<usize>,
@@ -76,6 +100,196 @@ impl Checksum {
     }
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct CacheEntry {
+    len: u64,
+    mtime_nanos: u128,
+    sha256: String,
+}
+
+/// Sidecar cache mapping an absolute file path to the size+mtime it was last hashed at.
+struct ChecksumCache {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<PathBuf, CacheEntry>>,
+}
+
+impl ChecksumCache {
+    fn load(path: PathBuf) -> Result<Self> {
+        let entries = if path.exists() {
+            let cache_str = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read checksum cache `{}`", path.display()))?;
+            serde_json::from_str(&cache_str)
+                .with_context(|| format!("failed to parse checksum cache `{}`", path.display()))?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self { path, entries: Mutex::new(entries) })
+    }
+
+    fn digest(&self, file: &Path) -> Result<String> {
+        let metadata = fs::metadata(file)
+            .with_context(|| format!("failed to read metadata for file `{}`", file.display()))?;
+        let len = metadata.len();
+        let mtime_nanos = metadata
+            .modified()
+            .with_context(|| format!("failed to read mtime for file `{}`", file.display()))?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let key = file.canonicalize().unwrap_or_else(|_| file.to_owned());
+
+        if let Some(entry) = self.entries.lock().expect("cache lock poisoned").get(&key) {
+            if entry.len == len && entry.mtime_nanos == mtime_nanos {
+                return Ok(entry.sha256.to_owned());
+            }
+        }
+
+        let digest = sha256::try_digest(file)
+            .with_context(|| format!("failed to get checksum for file `{}`", file.display()))?;
+
+        self.entries
+            .lock()
+            .expect("cache lock poisoned")
+            .insert(key, CacheEntry { len, mtime_nanos, sha256: digest.to_owned() });
+
+        Ok(digest)
+    }
+
+    fn persist(&self) -> Result<()> {
+        let entries = self.entries.lock().expect("cache lock poisoned");
+        let cache_str = serde_json::to_string(&*entries)?;
+        fs::write(&self.path, cache_str)
+            .with_context(|| format!("failed to write checksum cache `{}`", self.path.display()))?;
+
+        Ok(())
+    }
+}
+
+fn apply_package_field(checksum: &mut Checksum, set_package: Option<&str>, clear_package: bool) {
+    if let Some(digest) = set_package {
+        checksum.package = Some(digest.to_owned());
+    } else if clear_package {
+        checksum.package = None;
+    }
+}
+
+fn digest_file(file: &Path, cache: Option<&ChecksumCache>) -> Result<String> {
+    if let Some(cache) = cache {
+        return cache.digest(file);
+    }
+
+    sha256::try_digest(file)
+        .with_context(|| format!("failed to get checksum for file `{}`", file.display()))
+}
+
+#[derive(Debug, Default)]
+struct CheckReport {
+    mismatched: Vec<PathBuf>,
+    missing: Vec<PathBuf>,
+    untracked: Vec<PathBuf>,
+}
+
+impl CheckReport {
+    fn is_clean(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty() && self.untracked.is_empty()
+    }
+}
+
+fn print_check_report(pkg: &OsStr, report: &CheckReport) {
+    for file in &report.mismatched {
+        eprintln!("{}: checksum mismatch for `{}`", pkg.to_string_lossy(), file.display());
+    }
+    for file in &report.missing {
+        eprintln!("{}: recorded file missing from disk `{}`", pkg.to_string_lossy(), file.display());
+    }
+    for file in &report.untracked {
+        eprintln!(
+            "{}: untracked file not present in checksum manifest `{}`",
+            pkg.to_string_lossy(),
+            file.display()
+        );
+    }
+}
+
+/// Recursively list every regular file under `dir`, relative to `dir`, skipping the checksum manifest itself.
+fn walk_package_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    fn walk(dir: &Path, root: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        for entry in fs::read_dir(dir)
+            .with_context(|| format!("failed to read directory `{}`", dir.display()))?
+        {
+            let path = entry
+                .with_context(|| format!("failed to read directory `{}`", dir.display()))?
+                .path();
+            if path.is_dir() {
+                walk(&path, root, files)?;
+            } else if path.file_name() != Some(OsStr::new(".cargo-checksum.json")) {
+                files.push(path.strip_prefix(root).expect("path should be under root").to_owned());
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    walk(dir, dir, &mut files)?;
+    Ok(files)
+}
+
+fn check_package(
+    path: &Path,
+    checksum: &Checksum,
+    cache: Option<&ChecksumCache>,
+) -> Result<CheckReport> {
+    let mut report = CheckReport::default();
+
+    let results = checksum
+        .files
+        .par_iter()
+        .map(|(relative_file, recorded_digest)| -> Result<_> {
+            let file = path.join(relative_file);
+            if !file.exists() {
+                return Ok((relative_file.to_owned(), None));
+            }
+            let digest = digest_file(&file, cache)?;
+            Ok((relative_file.to_owned(), Some((digest, recorded_digest.to_owned()))))
+        })
+        .collect::<Vec<_>>();
+
+    for result in results {
+        let (relative_file, outcome) = result?;
+        match outcome {
+            None => report.missing.push(relative_file),
+            Some((digest, recorded)) if digest != recorded => report.mismatched.push(relative_file),
+            Some(_) => {}
+        }
+    }
+
+    for file in walk_package_files(path)? {
+        if !checksum.files.contains_key(&file) {
+            report.untracked.push(file);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Recompute the complete `files` map for a package from what's actually on disk, rather than
+/// from the set of paths already recorded in its checksum manifest.
+fn sync_package(
+    path: &Path,
+    cache: Option<&ChecksumCache>,
+) -> Result<BTreeMap<PathBuf, String>> {
+    walk_package_files(path)?
+        .into_par_iter()
+        .map(|relative_file| -> Result<_> {
+            let file = path.join(&relative_file);
+            let digest = digest_file(&file, cache)?;
+            Ok((relative_file, digest))
+        })
+        .collect()
+}
+
 fn get_packages(vendor: &Path) -> Result<Vec<OsString>> {
     Ok(fs::read_dir(vendor)
         .with_context(|| format!("failed to read vendor directory `{}`", vendor.display(),))?
@@ -83,6 +297,37 @@ fn get_packages(vendor: &Path) -> Result<Vec<OsString>> {
         .collect::<Vec<_>>())
 }
 
+/// Expand a glob pattern, matched relative to the vendor root, into the file list
+/// `process_files_in_vendor_dir` expects (`<package>/<path within package>`).
+fn expand_glob(vendor: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let matcher = GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .with_context(|| format!("invalid glob pattern `{}`", pattern))?
+        .compile_matcher();
+
+    let files = get_packages(vendor)?
+        .into_par_iter()
+        .map(|pkg| -> Result<Vec<PathBuf>> {
+            let pkg_dir = vendor.join(&pkg);
+            Ok(walk_package_files(&pkg_dir)?
+                .into_iter()
+                .map(|file| Path::new(&pkg).join(file))
+                .filter(|file_in_vendor_dir| matcher.is_match(file_in_vendor_dir))
+                .collect())
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+    if files.is_empty() {
+        bail!("glob pattern `{}` did not match any files under `{}`", pattern, vendor.display());
+    }
+
+    Ok(files)
+}
+
 fn print_completions<G: Generator>(gen: G, cmd: &mut Command) {
     generate(gen, cmd, cmd.get_name().to_string(), &mut io::stdout());
 }
@@ -99,6 +344,8 @@ fn process_files_in_vendor_dir<V: AsRef<Path>>(
     vendor: V,
     files_in_vendor_dir: &[PathBuf],
     ignore_missing: bool,
+    check: bool,
+    cache: Option<&ChecksumCache>,
 ) -> Result<()> {
     let vendor = vendor.as_ref();
 
@@ -114,23 +361,41 @@ fn process_files_in_vendor_dir<V: AsRef<Path>>(
         let full_file = vendor.join(file_in_vendor_dir);
         let file_in_pkg = file_parts[1..].iter().collect::<PathBuf>();
 
-        if ignore_missing && !full_file.exists() {
+        if (ignore_missing || check) && !full_file.exists() {
             return Ok((pkg, file_in_pkg, None));
         }
 
-        let digest = sha256::try_digest(&full_file).with_context(|| {
-            format!("failed to get checksum for file `{}`", full_file.display())
-        })?;
+        let digest = digest_file(&full_file, cache)?;
         Ok((pkg, file_in_pkg, Some(digest)))
     });
 
     let mut checksums: BTreeMap<OsString, Checksum> = BTreeMap::new();
+    let mut reports: BTreeMap<OsString, CheckReport> = BTreeMap::new();
     for result in results.collect::<Vec<_>>() {
         let (pkg, file_in_pkg, digest) = result?;
         if !checksums.contains_key(&pkg) {
             let cksum_file = vendor.join(&pkg).join(".cargo-checksum.json");
             checksums.insert(pkg.to_owned(), Checksum::new(&cksum_file)?);
         }
+
+        if check {
+            let recorded = checksums
+                .get(&pkg)
+                .expect("Checksum should be created")
+                .files
+                .get(&file_in_pkg);
+            let report = reports.entry(pkg).or_default();
+            match (recorded, &digest) {
+                (Some(_), None) => report.missing.push(file_in_pkg),
+                (None, Some(_)) => report.untracked.push(file_in_pkg),
+                (Some(recorded), Some(digest)) if recorded != digest => {
+                    report.mismatched.push(file_in_pkg)
+                }
+                _ => {}
+            }
+            continue;
+        }
+
         let files = &mut checksums.get_mut(&pkg).expect("Checksum should be created").files;
         if let Some(digest) = digest {
             files.insert(file_in_pkg, digest);
@@ -139,16 +404,105 @@ fn process_files_in_vendor_dir<V: AsRef<Path>>(
         }
     }
 
+    if check {
+        let mut clean = true;
+        for (pkg, report) in &reports {
+            if !report.is_clean() {
+                clean = false;
+                print_check_report(pkg, report);
+            }
+        }
+
+        return if clean {
+            Ok(())
+        } else {
+            bail!("vendored files do not match recorded checksums");
+        };
+    }
+
     write_checksums(checksums)
 }
 
+/// Options shared by `process_packages`, bundled to keep the function's argument list manageable.
+#[derive(Default)]
+struct PackageOptions<'a> {
+    ignore_missing: bool,
+    check: bool,
+    sync: bool,
+    set_package: Option<&'a str>,
+    clear_package: bool,
+    cache: Option<&'a ChecksumCache>,
+}
+
 fn process_packages<V: AsRef<Path>>(
     vendor: V,
     packages: &[OsString],
-    ignore_missing: bool,
+    options: &PackageOptions,
 ) -> Result<()> {
     let vendor = vendor.as_ref();
 
+    if (options.set_package.is_some() || options.clear_package) && packages.len() != 1 {
+        bail!(
+            "--set-package/--clear-package record a single package's crate-tarball digest and \
+             require exactly one package (got {}); pass a single `--packages <PKG>`",
+            packages.len()
+        );
+    }
+
+    if options.check {
+        let reports = packages
+            .par_iter()
+            .map(|pkg| -> Result<(OsString, CheckReport, Option<String>)> {
+                let path = Path::new(&vendor).join(pkg);
+                let cksum_file = path.join(".cargo-checksum.json");
+                let checksum = Checksum::new(&cksum_file)?;
+                let report = check_package(&path, &checksum, options.cache)?;
+                Ok((pkg.to_owned(), report, checksum.package))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut clean = true;
+        for (pkg, report, package) in &reports {
+            if let Some(package) = package {
+                eprintln!("{}: package = {}", pkg.to_string_lossy(), package);
+            }
+            if !report.is_clean() {
+                clean = false;
+                print_check_report(pkg, report);
+            }
+        }
+
+        return if clean {
+            Ok(())
+        } else {
+            bail!("vendor directory does not match recorded checksums");
+        };
+    }
+
+    if options.sync {
+        return packages.par_iter().try_for_each(|pkg| -> Result<()> {
+            let path = Path::new(&vendor).join(pkg);
+            let cksum_file = path.join(".cargo-checksum.json");
+            let mut checksum = Checksum::new(&cksum_file)?;
+            let synced = sync_package(&path, options.cache)?;
+
+            if !options.ignore_missing {
+                for relative_file in checksum.files.keys() {
+                    if !synced.contains_key(relative_file) {
+                        bail!(
+                            "recorded file missing from disk `{}`",
+                            path.join(relative_file).display()
+                        );
+                    }
+                }
+            }
+
+            checksum.files = synced;
+            apply_package_field(&mut checksum, options.set_package, options.clear_package);
+            checksum.write()
+        });
+    }
+
     packages.par_iter().try_for_each(|pkg| -> Result<()> {
         let path = Path::new(&vendor).join(pkg);
         let cksum_file = path.join(".cargo-checksum.json");
@@ -161,12 +515,10 @@ fn process_packages<V: AsRef<Path>>(
             .par_iter()
             .map(|relative_file| -> Result<_> {
                 let file = path.join(relative_file);
-                if ignore_missing && !file.exists() {
+                if options.ignore_missing && !file.exists() {
                     return Ok((relative_file.to_owned(), None));
                 }
-                let digest = sha256::try_digest(&file).with_context(|| {
-                    format!("failed to get checksum for file `{}`", file.display())
-                })?;
+                let digest = digest_file(&file, options.cache)?;
                 Ok((relative_file.to_owned(), Some(digest)))
             })
             .collect::<Vec<_>>();
@@ -180,6 +532,7 @@ fn process_packages<V: AsRef<Path>>(
             }
         }
 
+        apply_package_field(&mut checksum, options.set_package, options.clear_package);
         checksum.write()
     })
 }
@@ -198,19 +551,251 @@ fn main() -> Result<()> {
     }
     let thread_pool = thread_pool_builder.build()?;
 
-    thread_pool.install(|| {
+    let cache = args.cache.map(ChecksumCache::load).transpose()?;
+
+    let package_options = PackageOptions {
+        ignore_missing: args.ignore_missing,
+        check: args.check,
+        sync: args.sync,
+        set_package: args.set_package.as_deref(),
+        clear_package: args.clear_package,
+        cache: cache.as_ref(),
+    };
+
+    let result = thread_pool.install(|| {
         if !args.files.files_in_vendor_dir.is_empty() {
             process_files_in_vendor_dir(
                 &vendor,
                 &args.files.files_in_vendor_dir,
                 args.ignore_missing,
+                args.check,
+                cache.as_ref(),
+            )
+        } else if let Some(pattern) = &args.files.glob {
+            process_files_in_vendor_dir(
+                &vendor,
+                &expand_glob(&vendor, pattern)?,
+                args.ignore_missing,
+                args.check,
+                cache.as_ref(),
             )
         } else if args.files.all {
-            process_packages(&vendor, &get_packages(&vendor)?, args.ignore_missing)
+            process_packages(&vendor, &get_packages(&vendor)?, &package_options)
         } else {
-            process_packages(&vendor, &args.files.packages, args.ignore_missing)
+            process_packages(&vendor, &args.files.packages, &package_options)
         }
-    })?;
+    });
+
+    if let Some(cache) = &cache {
+        cache.persist()?;
+    }
+
+    result?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn write_checksum(cksum_file: &Path, files: BTreeMap<PathBuf, String>, package: Option<&str>) {
+        Checksum { files, package: package.map(str::to_owned), path: cksum_file.to_owned() }
+            .write()
+            .unwrap();
+    }
+
+    #[test]
+    fn process_packages_updates_changed_file_digest() {
+        let vendor = tempdir().unwrap();
+        let pkg_dir = vendor.path().join("demo-pkg");
+        fs::create_dir_all(&pkg_dir).unwrap();
+        fs::write(pkg_dir.join("lib.rs"), "fn main() {}").unwrap();
+
+        let cksum_file = pkg_dir.join(".cargo-checksum.json");
+        write_checksum(
+            &cksum_file,
+            BTreeMap::from([(PathBuf::from("lib.rs"), "stale".to_owned())]),
+            None,
+        );
+
+        let options = PackageOptions::default();
+        process_packages(vendor.path(), &[OsString::from("demo-pkg")], &options).unwrap();
+
+        let updated = Checksum::new(&cksum_file).unwrap();
+        let expected = sha256::try_digest(pkg_dir.join("lib.rs")).unwrap();
+        assert_eq!(updated.files.get(Path::new("lib.rs")), Some(&expected));
+    }
+
+    #[test]
+    fn process_packages_errors_on_vanished_file_without_ignore_missing() {
+        let vendor = tempdir().unwrap();
+        let pkg_dir = vendor.path().join("demo-pkg");
+        fs::create_dir_all(&pkg_dir).unwrap();
+
+        let cksum_file = pkg_dir.join(".cargo-checksum.json");
+        write_checksum(
+            &cksum_file,
+            BTreeMap::from([(PathBuf::from("gone.rs"), "deadbeef".to_owned())]),
+            None,
+        );
+
+        let options = PackageOptions::default();
+        assert!(process_packages(vendor.path(), &[OsString::from("demo-pkg")], &options).is_err());
+    }
+
+    #[test]
+    fn process_packages_ignore_missing_drops_vanished_file() {
+        let vendor = tempdir().unwrap();
+        let pkg_dir = vendor.path().join("demo-pkg");
+        fs::create_dir_all(&pkg_dir).unwrap();
+
+        let cksum_file = pkg_dir.join(".cargo-checksum.json");
+        write_checksum(
+            &cksum_file,
+            BTreeMap::from([(PathBuf::from("gone.rs"), "deadbeef".to_owned())]),
+            None,
+        );
+
+        let options = PackageOptions { ignore_missing: true, ..Default::default() };
+        process_packages(vendor.path(), &[OsString::from("demo-pkg")], &options).unwrap();
+
+        let updated = Checksum::new(&cksum_file).unwrap();
+        assert!(updated.files.is_empty());
+    }
+
+    #[test]
+    fn process_packages_sync_rebuilds_file_map_from_disk() {
+        let vendor = tempdir().unwrap();
+        let pkg_dir = vendor.path().join("demo-pkg");
+        fs::create_dir_all(&pkg_dir).unwrap();
+        fs::write(pkg_dir.join("new.rs"), "fn added() {}").unwrap();
+
+        let cksum_file = pkg_dir.join(".cargo-checksum.json");
+        write_checksum(
+            &cksum_file,
+            BTreeMap::from([(PathBuf::from("gone.rs"), "deadbeef".to_owned())]),
+            None,
+        );
+
+        let options = PackageOptions { sync: true, ignore_missing: true, ..Default::default() };
+        process_packages(vendor.path(), &[OsString::from("demo-pkg")], &options).unwrap();
+
+        let updated = Checksum::new(&cksum_file).unwrap();
+        assert!(!updated.files.contains_key(Path::new("gone.rs")));
+        assert!(updated.files.contains_key(Path::new("new.rs")));
+    }
+
+    #[test]
+    fn check_package_reports_mismatch_missing_and_untracked() {
+        let vendor = tempdir().unwrap();
+        let pkg_dir = vendor.path().join("demo-pkg");
+        fs::create_dir_all(&pkg_dir).unwrap();
+        fs::write(pkg_dir.join("lib.rs"), "fn main() {}").unwrap();
+        fs::write(pkg_dir.join("extra.rs"), "fn extra() {}").unwrap();
+
+        let checksum = Checksum {
+            files: BTreeMap::from([
+                (PathBuf::from("lib.rs"), "stale".to_owned()),
+                (PathBuf::from("missing.rs"), "deadbeef".to_owned()),
+            ]),
+            package: None,
+            path: pkg_dir.join(".cargo-checksum.json"),
+        };
+
+        let report = check_package(&pkg_dir, &checksum, None).unwrap();
+        assert_eq!(report.mismatched, vec![PathBuf::from("lib.rs")]);
+        assert_eq!(report.missing, vec![PathBuf::from("missing.rs")]);
+        assert_eq!(report.untracked, vec![PathBuf::from("extra.rs")]);
+    }
+
+    #[test]
+    fn expand_glob_does_not_cross_directory_boundaries() {
+        let vendor = tempdir().unwrap();
+        fs::create_dir_all(vendor.path().join("demo-pkg/src/nested")).unwrap();
+        fs::write(vendor.path().join("demo-pkg/build.rs"), "").unwrap();
+        fs::write(vendor.path().join("demo-pkg/src/nested/lib.rs"), "").unwrap();
+
+        let matched = expand_glob(vendor.path(), "demo-pkg/*.rs").unwrap();
+        assert_eq!(matched, vec![PathBuf::from("demo-pkg/build.rs")]);
+    }
+
+    #[test]
+    fn checksum_cache_reuses_entry_when_size_and_mtime_match() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("file.rs");
+        fs::write(&file, "hello").unwrap();
+
+        let metadata = fs::metadata(&file).unwrap();
+        let mtime_nanos =
+            metadata.modified().unwrap().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        let key = file.canonicalize().unwrap();
+
+        let cache = ChecksumCache {
+            path: dir.path().join("cache.json"),
+            entries: Mutex::new(BTreeMap::from([(
+                key,
+                CacheEntry { len: metadata.len(), mtime_nanos, sha256: "stale-but-cached".to_owned() },
+            )])),
+        };
+
+        assert_eq!(cache.digest(&file).unwrap(), "stale-but-cached");
+    }
+
+    #[test]
+    fn process_files_in_vendor_dir_check_detects_mismatch_without_rewriting() {
+        let vendor = tempdir().unwrap();
+        let pkg_dir = vendor.path().join("demo-pkg");
+        fs::create_dir_all(&pkg_dir).unwrap();
+        fs::write(pkg_dir.join("lib.rs"), "fn main() {}").unwrap();
+
+        let cksum_file = pkg_dir.join(".cargo-checksum.json");
+        write_checksum(
+            &cksum_file,
+            BTreeMap::from([(PathBuf::from("lib.rs"), "stale".to_owned())]),
+            None,
+        );
+
+        let result = process_files_in_vendor_dir(
+            vendor.path(),
+            &[PathBuf::from("demo-pkg/lib.rs")],
+            false,
+            true,
+            None,
+        );
+        assert!(result.is_err());
+
+        let untouched = Checksum::new(&cksum_file).unwrap();
+        assert_eq!(untouched.files.get(Path::new("lib.rs")), Some(&"stale".to_owned()));
+    }
+
+    #[test]
+    fn process_packages_cache_round_trips_through_persist() {
+        let vendor = tempdir().unwrap();
+        let pkg_dir = vendor.path().join("demo-pkg");
+        fs::create_dir_all(&pkg_dir).unwrap();
+        fs::write(pkg_dir.join("lib.rs"), "fn main() {}").unwrap();
+
+        let cksum_file = pkg_dir.join(".cargo-checksum.json");
+        write_checksum(
+            &cksum_file,
+            BTreeMap::from([(PathBuf::from("lib.rs"), "stale".to_owned())]),
+            None,
+        );
+
+        let cache_file = vendor.path().join("cache.json");
+        let cache = ChecksumCache::load(cache_file.clone()).unwrap();
+        let options = PackageOptions { cache: Some(&cache), ..Default::default() };
+        process_packages(vendor.path(), &[OsString::from("demo-pkg")], &options).unwrap();
+        cache.persist().unwrap();
+
+        let expected = sha256::try_digest(pkg_dir.join("lib.rs")).unwrap();
+        let updated = Checksum::new(&cksum_file).unwrap();
+        assert_eq!(updated.files.get(Path::new("lib.rs")), Some(&expected));
+
+        let reloaded = ChecksumCache::load(cache_file).unwrap();
+        assert_eq!(reloaded.digest(&pkg_dir.join("lib.rs")).unwrap(), expected);
+    }
+}